@@ -1,7 +1,8 @@
 use crate::timeline::Frame;
 use avm1_parser::parse_cfg;
-use avm1_types::cfg::{Action, Cfg, CfgBlock, CfgFlow};
+use avm1_types::cfg::{Action, Cfg, CfgFlow, CfgLabel};
 use avm1_types::PushValue;
+use std::collections::HashMap;
 
 #[derive(Clone, Debug)]
 pub enum Value {
@@ -32,6 +33,116 @@ impl Value {
             _ => None,
         }
     }
+
+    /// The ECMA-262 `ToBoolean` abstract operation.
+    pub fn to_boolean(&self) -> bool {
+        match self {
+            Value::Undefined | Value::Null => false,
+            Value::Bool(x) => *x,
+            Value::I32(x) => *x != 0,
+            Value::F32(x) => *x != 0.0 && !x.is_nan(),
+            Value::F64(x) => *x != 0.0 && !x.is_nan(),
+            Value::Str(s) => !s.is_empty(),
+            Value::OpRes(_) => true,
+        }
+    }
+
+    /// The ECMA-262 `ToNumber` abstract operation (AVM1 only has `f64`,
+    /// never a distinct integer/float split, so this is the one numeric
+    /// coercion every op needs).
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Value::Undefined => f64::NAN,
+            Value::Null => 0.0,
+            Value::Bool(false) => 0.0,
+            Value::Bool(true) => 1.0,
+            Value::I32(x) => f64::from(*x),
+            Value::F32(x) => f64::from(*x),
+            Value::F64(x) => *x,
+            Value::Str(s) => {
+                let s = s.trim();
+                if s.is_empty() {
+                    0.0
+                } else if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+                    i64::from_str_radix(hex, 16).map_or(f64::NAN, |x| x as f64)
+                } else {
+                    s.parse().unwrap_or(f64::NAN)
+                }
+            }
+            // Not a real coercion, but there's no value to coerce yet.
+            Value::OpRes(_) => f64::NAN,
+        }
+    }
+
+    /// The ECMA-262 `ToString` abstract operation.
+    pub fn to_string(&self) -> String {
+        match self {
+            Value::Undefined => "undefined".to_string(),
+            Value::Null => "null".to_string(),
+            Value::Bool(x) => x.to_string(),
+            Value::I32(x) => x.to_string(),
+            Value::F32(x) => format_number(f64::from(*x)),
+            Value::F64(x) => format_number(*x),
+            Value::Str(s) => s.clone(),
+            Value::OpRes(i) => format!("<op {}>", i),
+        }
+    }
+}
+
+/// Formats a number the way AVM1/ECMA-262 does: integral values print
+/// without a trailing `.0` (e.g. `"1"`, not `"1.0"`), and infinities print
+/// as `"Infinity"`/`"-Infinity"`. Other values use Rust's `f64` formatting,
+/// which never switches to exponent notation.
+fn format_number(x: f64) -> String {
+    if x.is_infinite() {
+        let s = if x > 0.0 { "Infinity" } else { "-Infinity" };
+        s.to_string()
+    } else if x == (x as i64 as f64) {
+        (x as i64).to_string()
+    } else {
+        x.to_string()
+    }
+}
+
+fn push_binary(ops: &mut Vec<Op>, stack: &mut Vec<Value>, op: BinOp) {
+    let rhs = stack.pop().unwrap();
+    let lhs = stack.pop().unwrap();
+    ops.push(Op::Binary(op, lhs, rhs));
+    stack.push(Value::OpRes(ops.len() - 1));
+}
+
+fn push_unary(ops: &mut Vec<Op>, stack: &mut Vec<Value>, op: UnOp) {
+    let x = stack.pop().unwrap();
+    ops.push(Op::Unary(op, x));
+    stack.push(Value::OpRes(ops.len() - 1));
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum BinOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+
+    Equals,
+    Less,
+    Greater,
+
+    And,
+    Or,
+
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+    ShiftRightUnsigned,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum UnOp {
+    Not,
 }
 
 #[derive(Debug)]
@@ -46,9 +157,21 @@ pub enum Op {
     GetVar(String),
     SetVar(String, Value),
 
-    Call(Value, Vec<Value>),
-    // FIXME(eddyb) integrate with GetMember.
-    CallMethod(Value, String, Vec<Value>),
+    GetMember(Value, Value),
+    SetMember(Value, Value, Value),
+
+    Binary(BinOp, Value, Value),
+    Unary(UnOp, Value),
+
+    // NOTE(eddyb) `this` is `Value::Undefined` for a plain function call;
+    // a method call (`obj.method(...)`) lowers to a `GetMember` for the
+    // callee, with `obj` threaded through here as `this`.
+    Call(Value, Value, Vec<Value>),
+
+    // NOTE(eddyb) `target` is an index into `Code::ops`, patched in a
+    // second pass once every block's starting op index is known.
+    Jump(usize),
+    JumpIf(Value, usize),
 }
 
 #[derive(Debug)]
@@ -65,137 +188,297 @@ impl Code {
 
     pub fn compile(cfg: Cfg) -> Self {
         let mut consts = vec![];
-        let mut regs = vec![];
+        let mut regs: Vec<Value> = vec![];
         let mut stack = vec![];
         let mut ops = vec![];
 
-        // HACK(eddyb) this hides the warnings / inference errors about `regs`.
-        // FIXME(eddyb) remove after register writes are implemented.
-        regs.push(Value::Undefined);
-        regs.pop();
+        // Map from a block's label to the op index its code begins at,
+        // filled in as blocks are compiled (in order), and `jump_fixups`
+        // collects `(op index of Jump/JumpIf, target label)` pairs to be
+        // patched into real op indices once every block has been visited.
+        let mut block_starts = HashMap::new();
+        let mut jump_fixups: Vec<(usize, CfgLabel)> = vec![];
 
-        // FIXME(demurgos) Handle control flow, we're currently only compiling the first block
-        let block: CfgBlock = cfg.blocks.into_vec().remove(0);
+        let blocks = cfg.blocks.into_vec();
+        let block_labels: Vec<CfgLabel> = blocks.iter().map(|block| block.label).collect();
 
-        for action in block.actions {
-            match action {
-                Action::Play => ops.push(Op::Play),
-                Action::Stop => ops.push(Op::Stop),
-                Action::GotoFrame(goto) => {
-                    ops.push(Op::GotoFrame(Frame(goto.frame as u16)));
-                }
-                Action::GotoLabel(goto) => {
-                    ops.push(Op::GotoLabel(goto.label));
-                }
-                Action::GetUrl(get_url) => {
-                    ops.push(Op::GetUrl(get_url.url, get_url.target));
-                }
-                Action::ConstantPool(pool) => {
-                    consts = pool.pool;
-                }
-                Action::Push(push) => {
-                    stack.extend(push.values.into_iter().map(|value| match value {
-                        PushValue::Undefined => Value::Undefined,
-                        PushValue::Null => Value::Null,
-                        PushValue::Boolean(x) => Value::Bool(x),
-                        PushValue::Sint32(x) => Value::I32(x),
-                        PushValue::Float32(x) => Value::F32(x),
-                        PushValue::Float64(x) => Value::F64(x),
-                        PushValue::String(s) => Value::Str(s),
-
-                        // FIXME(eddyb) avoid per-use cloning.
-                        PushValue::Constant(i) => Value::Str(consts[i as usize].to_string()),
-                        PushValue::Register(i) => regs[i as usize].clone(),
-                    }));
-                }
-                Action::Pop => {
-                    stack.pop();
-                }
-                Action::GetVariable => match stack.pop().unwrap() {
-                    Value::Str(name) => {
-                        ops.push(Op::GetVar(name));
-                        stack.push(Value::OpRes(ops.len() - 1));
+        'blocks: for (block_index, block) in blocks.into_iter().enumerate() {
+            block_starts.insert(block.label, ops.len());
+            let next_block_label = block_labels.get(block_index + 1).copied();
+
+            // Set when an action bails out early (residual/too-dynamic
+            // stack state), so `block.flow` below isn't processed against
+            // a stack it can no longer trust.
+            let mut bailed = false;
+
+            for action in block.actions {
+                match action {
+                    Action::Play => ops.push(Op::Play),
+                    Action::Stop => ops.push(Op::Stop),
+                    Action::GotoFrame(goto) => {
+                        ops.push(Op::GotoFrame(Frame(goto.frame as u16)));
                     }
-                    name => {
-                        eprintln!("avm1: too dynamic GetVar({:?})", name);
-                        break;
+                    Action::GotoLabel(goto) => {
+                        ops.push(Op::GotoLabel(goto.label));
                     }
-                },
-                Action::SetVariable => {
-                    let value = stack.pop().unwrap();
-                    match stack.pop().unwrap() {
+                    Action::GetUrl(get_url) => {
+                        ops.push(Op::GetUrl(get_url.url, get_url.target));
+                    }
+                    Action::ConstantPool(pool) => {
+                        consts = pool.pool;
+                    }
+                    Action::Push(push) => {
+                        stack.extend(push.values.into_iter().map(|value| match value {
+                            PushValue::Undefined => Value::Undefined,
+                            PushValue::Null => Value::Null,
+                            PushValue::Boolean(x) => Value::Bool(x),
+                            PushValue::Sint32(x) => Value::I32(x),
+                            PushValue::Float32(x) => Value::F32(x),
+                            PushValue::Float64(x) => Value::F64(x),
+                            PushValue::String(s) => Value::Str(s),
+
+                            // FIXME(eddyb) avoid per-use cloning.
+                            PushValue::Constant(i) => Value::Str(consts[i as usize].to_string()),
+                            // A register can be read before it's ever
+                            // written in this code blob, e.g. compiler-
+                            // emitted functions that get their arguments
+                            // pre-populated into registers by the caller.
+                            PushValue::Register(i) => {
+                                regs.get(i as usize).cloned().unwrap_or(Value::Undefined)
+                            }
+                        }));
+                    }
+                    Action::Pop => {
+                        stack.pop();
+                    }
+                    Action::GetVariable => match stack.pop().unwrap() {
                         Value::Str(name) => {
-                            ops.push(Op::SetVar(name, value));
+                            ops.push(Op::GetVar(name));
                             stack.push(Value::OpRes(ops.len() - 1));
                         }
                         name => {
-                            eprintln!("avm1: too dynamic SetVar({:?}, {:?})", name, value);
+                            eprintln!("avm1: too dynamic GetVar({:?})", name);
+                            bailed = true;
                             break;
                         }
+                    },
+                    Action::SetVariable => {
+                        let value = stack.pop().unwrap();
+                        match stack.pop().unwrap() {
+                            Value::Str(name) => {
+                                ops.push(Op::SetVar(name, value));
+                                stack.push(Value::OpRes(ops.len() - 1));
+                            }
+                            name => {
+                                eprintln!("avm1: too dynamic SetVar({:?}, {:?})", name, value);
+                                bailed = true;
+                                break;
+                            }
+                        }
                     }
-                }
-                Action::CallFunction => {
-                    let name = stack.pop().unwrap();
-                    let arg_count = stack.pop().unwrap();
-                    match (name, arg_count.as_i32()) {
-                        (Value::Str(name), Some(arg_count)) => {
-                            let args = (0..arg_count).map(|_| stack.pop().unwrap()).collect();
-                            ops.push(Op::GetVar(name));
-                            ops.push(Op::Call(Value::OpRes(ops.len() - 1), args));
-                            stack.push(Value::OpRes(ops.len() - 1));
+                    Action::StoreRegister(store_register) => {
+                        let i = store_register.register as usize;
+                        // StoreRegister doesn't pop: it only snapshots the
+                        // current top of stack into the register.
+                        let value = stack.last().unwrap().clone();
+                        if i >= regs.len() {
+                            regs.resize(i + 1, Value::Undefined);
                         }
-                        (name, _) => {
-                            eprintln!(
-                                "avm1: too dynamic CallFunction({:?}, {:?})",
-                                name, arg_count
-                            );
-                            break;
+                        regs[i] = value;
+                    }
+                    Action::DefineLocal => {
+                        let value = stack.pop().unwrap();
+                        match stack.pop().unwrap() {
+                            Value::Str(name) => {
+                                ops.push(Op::SetVar(name, value));
+                            }
+                            name => {
+                                eprintln!("avm1: too dynamic DefineLocal({:?}, {:?})", name, value);
+                                bailed = true;
+                                break;
+                            }
                         }
                     }
-                }
-                Action::CallMethod => {
-                    let mut name = stack.pop().unwrap();
-                    let this = stack.pop().unwrap();
-                    let arg_count = stack.pop().unwrap();
-
-                    if let Value::Str(s) = &name {
-                        if s.is_empty() {
-                            name = Value::Undefined;
+                    Action::DefineLocal2 => match stack.pop().unwrap() {
+                        Value::Str(name) => {
+                            ops.push(Op::SetVar(name, Value::Undefined));
+                        }
+                        name => {
+                            eprintln!("avm1: too dynamic DefineLocal2({:?})", name);
+                            bailed = true;
+                            break;
+                        }
+                    },
+                    Action::CallFunction => {
+                        let name = stack.pop().unwrap();
+                        let arg_count = stack.pop().unwrap();
+                        match (name, arg_count.as_i32()) {
+                            (Value::Str(name), Some(arg_count)) => {
+                                let args = (0..arg_count).map(|_| stack.pop().unwrap()).collect();
+                                ops.push(Op::GetVar(name));
+                                let callee = Value::OpRes(ops.len() - 1);
+                                ops.push(Op::Call(callee, Value::Undefined, args));
+                                stack.push(Value::OpRes(ops.len() - 1));
+                            }
+                            (name, _) => {
+                                eprintln!(
+                                    "avm1: too dynamic CallFunction({:?}, {:?})",
+                                    name, arg_count
+                                );
+                                bailed = true;
+                                break;
+                            }
                         }
                     }
+                    Action::CallMethod => {
+                        let mut name = stack.pop().unwrap();
+                        let this = stack.pop().unwrap();
+                        let arg_count = stack.pop().unwrap();
 
-                    match (name, arg_count.as_i32()) {
-                        (Value::Undefined, Some(arg_count)) => {
-                            let args = (0..arg_count).map(|_| stack.pop().unwrap()).collect();
-                            ops.push(Op::Call(this, args));
-                            stack.push(Value::OpRes(ops.len() - 1));
+                        if let Value::Str(s) = &name {
+                            if s.is_empty() {
+                                name = Value::Undefined;
+                            }
                         }
-                        (Value::Str(name), Some(arg_count)) => {
-                            let args = (0..arg_count).map(|_| stack.pop().unwrap()).collect();
-                            ops.push(Op::CallMethod(this, name, args));
-                            stack.push(Value::OpRes(ops.len() - 1));
+
+                        match (name, arg_count.as_i32()) {
+                            (Value::Undefined, Some(arg_count)) => {
+                                let args = (0..arg_count).map(|_| stack.pop().unwrap()).collect();
+                                // No method name: `this` itself is the callee.
+                                ops.push(Op::Call(this, Value::Undefined, args));
+                                stack.push(Value::OpRes(ops.len() - 1));
+                            }
+                            (Value::Str(name), Some(arg_count)) => {
+                                let args = (0..arg_count).map(|_| stack.pop().unwrap()).collect();
+                                ops.push(Op::GetMember(this.clone(), Value::Str(name)));
+                                let callee = Value::OpRes(ops.len() - 1);
+                                ops.push(Op::Call(callee, this, args));
+                                stack.push(Value::OpRes(ops.len() - 1));
+                            }
+                            (name, _) => {
+                                eprintln!(
+                                    "avm1: too dynamic CallMethod({:?}, {:?})",
+                                    name, arg_count
+                                );
+                                bailed = true;
+                                break;
+                            }
                         }
-                        (name, _) => {
-                            eprintln!("avm1: too dynamic CallMethod({:?}, {:?})", name, arg_count);
-                            break;
+                    }
+                    Action::GetMember => {
+                        let name = stack.pop().unwrap();
+                        let obj = stack.pop().unwrap();
+                        ops.push(Op::GetMember(obj, name));
+                        stack.push(Value::OpRes(ops.len() - 1));
+                    }
+                    Action::SetMember => {
+                        let value = stack.pop().unwrap();
+                        let name = stack.pop().unwrap();
+                        let obj = stack.pop().unwrap();
+                        ops.push(Op::SetMember(obj, name, value));
+                        stack.push(Value::OpRes(ops.len() - 1));
+                    }
+                    Action::Add2 => push_binary(&mut ops, &mut stack, BinOp::Add),
+                    Action::Subtract => push_binary(&mut ops, &mut stack, BinOp::Subtract),
+                    Action::Multiply => push_binary(&mut ops, &mut stack, BinOp::Multiply),
+                    Action::Divide => push_binary(&mut ops, &mut stack, BinOp::Divide),
+                    Action::Modulo => push_binary(&mut ops, &mut stack, BinOp::Modulo),
+                    Action::Equals2 => push_binary(&mut ops, &mut stack, BinOp::Equals),
+                    Action::Less2 => push_binary(&mut ops, &mut stack, BinOp::Less),
+                    Action::Greater => push_binary(&mut ops, &mut stack, BinOp::Greater),
+                    Action::LogicalAnd => push_binary(&mut ops, &mut stack, BinOp::And),
+                    Action::LogicalOr => push_binary(&mut ops, &mut stack, BinOp::Or),
+                    Action::LogicalNot => push_unary(&mut ops, &mut stack, UnOp::Not),
+                    Action::BitAnd => push_binary(&mut ops, &mut stack, BinOp::BitAnd),
+                    Action::BitOr => push_binary(&mut ops, &mut stack, BinOp::BitOr),
+                    Action::BitXor => push_binary(&mut ops, &mut stack, BinOp::BitXor),
+                    Action::ShiftLeft => push_binary(&mut ops, &mut stack, BinOp::ShiftLeft),
+                    Action::ShiftRight => push_binary(&mut ops, &mut stack, BinOp::ShiftRight),
+                    Action::ShiftRight2 => {
+                        push_binary(&mut ops, &mut stack, BinOp::ShiftRightUnsigned)
+                    }
+                    _ => {
+                        eprintln!("unknown action: {:?}", action);
+                        bailed = true;
+                        break;
+                    }
+                }
+            }
+
+            // An action already bailed out, so the stack can no longer be
+            // trusted to match what `block.flow` expects (e.g. an `If`
+            // needing a test value that was never pushed); stop here
+            // instead of popping from a stack that isn't what it thinks.
+            if bailed {
+                eprintln!(
+                    "avm1: non-reconstructible expression, bailed out mid-block {:?}",
+                    block.label,
+                );
+                stack.clear();
+                break 'blocks;
+            }
+
+            match block.flow {
+                // All of frames are loaded ahead of time, no waiting needed.
+                CfgFlow::WaitForFrame(_) => {}
+                CfgFlow::WaitForFrame2(_) => {
+                    stack.pop();
+                }
+                // Blocks are compiled in the same order the CFG lists them,
+                // so falling through to the next block needs no op at all;
+                // anything else (loop back-edges in particular) needs an
+                // explicit jump to reach its target.
+                CfgFlow::Simple(target) => {
+                    if let Some(target) = target {
+                        if Some(target) != next_block_label {
+                            ops.push(Op::Jump(usize::MAX));
+                            jump_fixups.push((ops.len() - 1, target));
                         }
                     }
                 }
+                CfgFlow::If(then_label, else_label) => {
+                    let test = stack.pop().unwrap();
+                    ops.push(Op::JumpIf(test, usize::MAX));
+                    jump_fixups.push((ops.len() - 1, then_label));
+                    ops.push(Op::Jump(usize::MAX));
+                    jump_fixups.push((ops.len() - 1, else_label));
+                }
                 _ => {
-                    eprintln!("unknown action: {:?}", action);
-                    break;
+                    eprintln!("unknown flow: {:?}", block.flow);
                 }
             }
-        }
 
-        match block.flow {
-            // All of frames are loaded ahead of time, no waiting needed.
-            CfgFlow::WaitForFrame(_) => {}
-            CfgFlow::WaitForFrame2(_) => {
-                stack.pop();
+            // The operand stack has to be empty at every block boundary:
+            // anything left on it can't be reconstructed into an
+            // expression once control flow has split into multiple blocks.
+            if !stack.is_empty() {
+                eprintln!(
+                    "avm1: non-reconstructible expression, {} residual value(s) on the stack \
+                     at the end of block {:?}",
+                    stack.len(),
+                    block.label,
+                );
+                stack.clear();
+                break 'blocks;
             }
-            _ => {
-                eprintln!("unknown flow: {:?}", block.flow);
+        }
+
+        for (op_index, target_label) in jump_fixups {
+            // A target block may never have been compiled if we bailed out
+            // of `'blocks` (e.g. due to residual stack) right after queuing
+            // this fixup; leave the placeholder target in place rather than
+            // panicking on the missing `block_starts` entry.
+            match block_starts.get(&target_label) {
+                Some(&target) => match &mut ops[op_index] {
+                    Op::Jump(t) | Op::JumpIf(_, t) => *t = target,
+                    _ => unreachable!(),
+                },
+                None => {
+                    eprintln!(
+                        "avm1: jump target block {:?} was never compiled",
+                        target_label
+                    );
+                }
             }
         }
 
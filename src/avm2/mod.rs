@@ -0,0 +1,137 @@
+//! AVM2/ABC (ActionScript Byte Code) support, the AS3 counterpart to
+//! `crate::avm1`. Unlike AVM1, property access is namespace-qualified (see
+//! `names`) rather than keyed by flat strings, so every op that touches a
+//! property carries a `Multiname` instead of a `String`.
+
+pub mod names;
+
+use crate::avm1::Value;
+use names::{ConstantPool, Multiname};
+
+#[derive(Debug)]
+pub enum Op {
+    GetProperty(Value, Multiname),
+    SetProperty(Value, Multiname, Value),
+    CallProperty(Value, Multiname, Vec<Value>),
+    // FIXME(eddyb) this should resolve against the live scope stack, not
+    // just push a placeholder `OpRes`.
+    FindPropStrict(Multiname),
+    NewObject(Vec<(Value, Value)>),
+
+    // ABC keeps an explicit scope stack (distinct from the operand stack)
+    // that `findpropstrict`/etc. search from the innermost scope outward;
+    // `compile` doesn't track it, it only records each push and pop as an
+    // op in `ops`.
+    PushScope(Value),
+    PopScope,
+}
+
+#[derive(Debug)]
+pub struct Code {
+    pub ops: Vec<Op>,
+}
+
+impl Code {
+    /// Compiles a single ABC method body's bytecode (the `code` field of a
+    /// `method_body_info`, which precedes its exception table).
+    pub fn compile(bytecode: &[u8], constant_pool: &ConstantPool) -> Self {
+        let mut reader = Reader { bytes: bytecode, pos: 0 };
+        let mut stack = vec![];
+        let mut ops = vec![];
+
+        while let Some(opcode) = reader.read_u8() {
+            match opcode {
+                // getproperty
+                0x66 => {
+                    let name = constant_pool.multiname(reader.read_u30()).clone();
+                    let obj = stack.pop().unwrap();
+                    ops.push(Op::GetProperty(obj, name));
+                    stack.push(Value::OpRes(ops.len() - 1));
+                }
+                // setproperty
+                0x61 => {
+                    let name = constant_pool.multiname(reader.read_u30()).clone();
+                    let value = stack.pop().unwrap();
+                    let obj = stack.pop().unwrap();
+                    ops.push(Op::SetProperty(obj, name, value));
+                }
+                // callproperty
+                0x46 => {
+                    let name = constant_pool.multiname(reader.read_u30()).clone();
+                    let arg_count = reader.read_u30();
+                    let args = (0..arg_count).map(|_| stack.pop().unwrap()).collect();
+                    let obj = stack.pop().unwrap();
+                    ops.push(Op::CallProperty(obj, name, args));
+                    stack.push(Value::OpRes(ops.len() - 1));
+                }
+                // findpropstrict
+                0x5d => {
+                    let name = constant_pool.multiname(reader.read_u30()).clone();
+                    ops.push(Op::FindPropStrict(name));
+                    stack.push(Value::OpRes(ops.len() - 1));
+                }
+                // newobject
+                0x55 => {
+                    let field_count = reader.read_u30() as usize;
+                    // Each field contributes a (name, value) pair, popped
+                    // value-then-name just like AVM1's `SetMember`; keep the
+                    // pairs so the op stays reconstructible.
+                    let mut fields: Vec<(Value, Value)> = (0..field_count)
+                        .map(|_| {
+                            let value = stack.pop().unwrap();
+                            let name = stack.pop().unwrap();
+                            (name, value)
+                        })
+                        .collect();
+                    fields.reverse();
+                    ops.push(Op::NewObject(fields));
+                    stack.push(Value::OpRes(ops.len() - 1));
+                }
+                // pushscope
+                0x30 => {
+                    let scope = stack.pop().unwrap();
+                    ops.push(Op::PushScope(scope));
+                }
+                // popscope
+                0x1d => ops.push(Op::PopScope),
+                _ => {
+                    eprintln!("avm2: unknown opcode: {:#04x}", opcode);
+                    break;
+                }
+            }
+        }
+
+        Code { ops }
+    }
+}
+
+/// A cursor over ABC-encoded bytes, which are little more than a sequence
+/// of opcodes followed by `u30`-encoded (LEB128, 30 bits wide) operands.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn read_u8(&mut self) -> Option<u8> {
+        let byte = *self.bytes.get(self.pos)?;
+        self.pos += 1;
+        Some(byte)
+    }
+
+    fn read_u30(&mut self) -> u32 {
+        // ABC's variable-length encoding is at most 5 bytes (7 payload bits
+        // each): don't trust untrusted/truncated bytecode to terminate the
+        // encoding itself, or a run of continuation bytes pushes the shift
+        // amount (`i * 7`) past 31 and overflows.
+        let mut result = 0u32;
+        for i in 0..5 {
+            let byte = self.read_u8().unwrap_or(0);
+            result |= u32::from(byte & 0x7f) << (i * 7);
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        result
+    }
+}
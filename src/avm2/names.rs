@@ -0,0 +1,61 @@
+/// An AVM2 namespace: a qualifier on a name, distinguishing e.g. the public
+/// `flash.display.Sprite` from a private or protected member sharing the
+/// same local name. AVM1 has no equivalent, since its variable/member names
+/// are flat strings.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Namespace {
+    Public(String),
+    Private(String),
+    Protected(String),
+    PackageInternal(String),
+    Explicit(String),
+    StaticProtected(String),
+    /// The wildcard namespace (`*`), matching any namespace.
+    Any,
+}
+
+/// A fully-qualified name: a namespace plus a local name.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct QName {
+    pub ns: Namespace,
+    pub name: String,
+}
+
+/// A name as it appears in an ABC instruction operand: either already a
+/// single `QName`, or still qualified by a *set* of namespaces to be
+/// resolved against the running scope stack when the property is looked up
+/// (the `Any` name, e.g. `obj[computedName]`, is resolved dynamically and
+/// carries no static namespace information at all).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Multiname {
+    QName(QName),
+    Multiname {
+        name: String,
+        namespace_set: Vec<Namespace>,
+    },
+    Any,
+}
+
+/// The subset of the ABC constant pool that name resolution needs: ABC
+/// instructions reference multinames (and the strings/namespaces they're
+/// built from) by index into pools parsed once per `abcFile`, never inline.
+#[derive(Debug, Default)]
+pub struct ConstantPool {
+    pub strings: Vec<String>,
+    pub namespaces: Vec<Namespace>,
+    pub multinames: Vec<Multiname>,
+}
+
+impl ConstantPool {
+    pub fn string(&self, index: u32) -> &str {
+        &self.strings[index as usize]
+    }
+
+    pub fn namespace(&self, index: u32) -> &Namespace {
+        &self.namespaces[index as usize]
+    }
+
+    pub fn multiname(&self, index: u32) -> &Multiname {
+        &self.multinames[index as usize]
+    }
+}
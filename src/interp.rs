@@ -0,0 +1,230 @@
+use crate::avm1::{BinOp, Code, Op, UnOp, Value};
+use crate::timeline::Frame;
+use std::collections::HashMap;
+
+/// The host functions a `Code` can reach through `Op::Call`, i.e. the
+/// built-ins (and user-defined functions) that live outside any one
+/// timeline's variable scope.
+pub trait Host {
+    fn call(&mut self, callee: &Value, this: &Value, args: &[Value]) -> Value;
+    fn get_url(&mut self, url: &str, target: &str);
+}
+
+/// An activation record for one `Code::run`: the `Frame` being driven, its
+/// variable scope, and the table of host built-ins `Call`/`CallMethod` (now
+/// just `Call`, with `this`) dispatch into.
+pub struct Context<'a> {
+    pub frame: &'a mut Frame,
+    pub vars: &'a mut HashMap<String, Value>,
+    pub host: &'a mut dyn Host,
+}
+
+impl Code {
+    /// Runs `self.ops` against `ctx`, one op at a time. Each op's result (if
+    /// any) is stored at `results[pc]`, so `Value::OpRes(i)` always means
+    /// "the result of op `i`" regardless of execution order, matching how
+    /// `compile` numbers them (as static indices into `ops`, not execution
+    /// order, which `Jump`/`JumpIf` can skip through).
+    pub fn run(&self, ctx: &mut Context) {
+        let mut results: Vec<Value> = vec![Value::Undefined; self.ops.len()];
+
+        let mut pc = 0;
+        while pc < self.ops.len() {
+            let result = match &self.ops[pc] {
+                Op::Play => {
+                    ctx.frame.play();
+                    Value::Undefined
+                }
+                Op::Stop => {
+                    ctx.frame.stop();
+                    Value::Undefined
+                }
+                Op::GotoFrame(frame) => {
+                    ctx.frame.goto_frame(frame);
+                    Value::Undefined
+                }
+                Op::GotoLabel(label) => {
+                    ctx.frame.goto_label(label);
+                    Value::Undefined
+                }
+                Op::GetUrl(url, target) => {
+                    ctx.host.get_url(url, target);
+                    Value::Undefined
+                }
+
+                Op::GetVar(name) => ctx.vars.get(name).cloned().unwrap_or(Value::Undefined),
+                Op::SetVar(name, value) => {
+                    ctx.vars.insert(name.clone(), resolve(&results, value));
+                    Value::Undefined
+                }
+
+                // NOTE(eddyb) there's no object model yet, so members are
+                // resolved as if every object were the variable scope.
+                Op::GetMember(_obj, name) => {
+                    let name = resolve(&results, name).to_string();
+                    ctx.vars.get(&name).cloned().unwrap_or(Value::Undefined)
+                }
+                Op::SetMember(_obj, name, value) => {
+                    let name = resolve(&results, name).to_string();
+                    ctx.vars.insert(name, resolve(&results, value));
+                    Value::Undefined
+                }
+
+                Op::Binary(op, lhs, rhs) => {
+                    eval_binary(*op, resolve(&results, lhs), resolve(&results, rhs))
+                }
+                Op::Unary(op, x) => eval_unary(*op, resolve(&results, x)),
+
+                Op::Call(callee, this, args) => {
+                    let callee = resolve(&results, callee);
+                    let this = resolve(&results, this);
+                    let args: Vec<Value> = args.iter().map(|v| resolve(&results, v)).collect();
+                    ctx.host.call(&callee, &this, &args)
+                }
+
+                Op::Jump(target) => {
+                    pc = *target;
+                    continue;
+                }
+                Op::JumpIf(test, target) => {
+                    if resolve(&results, test).to_boolean() {
+                        pc = *target;
+                        continue;
+                    }
+                    Value::Undefined
+                }
+            };
+
+            results[pc] = result;
+            pc += 1;
+        }
+    }
+}
+
+/// Looks up the `Value` an op actually produced if it's an `OpRes`,
+/// otherwise the value is already a literal and needs no resolution.
+fn resolve(results: &[Value], value: &Value) -> Value {
+    match value {
+        Value::OpRes(i) => results[*i].clone(),
+        other => other.clone(),
+    }
+}
+
+fn eval_binary(op: BinOp, lhs: Value, rhs: Value) -> Value {
+    match op {
+        // `Add2` is the one arithmetic op that isn't purely numeric: if
+        // either side is a string, it's concatenation, not addition.
+        BinOp::Add => {
+            if matches!(lhs, Value::Str(_)) || matches!(rhs, Value::Str(_)) {
+                Value::Str(lhs.to_string() + &rhs.to_string())
+            } else {
+                Value::F64(lhs.to_f64() + rhs.to_f64())
+            }
+        }
+        BinOp::Subtract => Value::F64(lhs.to_f64() - rhs.to_f64()),
+        BinOp::Multiply => Value::F64(lhs.to_f64() * rhs.to_f64()),
+        BinOp::Divide => Value::F64(lhs.to_f64() / rhs.to_f64()),
+        BinOp::Modulo => Value::F64(lhs.to_f64() % rhs.to_f64()),
+
+        BinOp::Equals => Value::Bool(values_equal(&lhs, &rhs)),
+        BinOp::Less => Value::Bool(lhs.to_f64() < rhs.to_f64()),
+        BinOp::Greater => Value::Bool(lhs.to_f64() > rhs.to_f64()),
+
+        BinOp::And => {
+            if lhs.to_boolean() {
+                rhs
+            } else {
+                lhs
+            }
+        }
+        BinOp::Or => {
+            if lhs.to_boolean() {
+                lhs
+            } else {
+                rhs
+            }
+        }
+
+        BinOp::BitAnd => Value::I32(to_i32(&lhs) & to_i32(&rhs)),
+        BinOp::BitOr => Value::I32(to_i32(&lhs) | to_i32(&rhs)),
+        BinOp::BitXor => Value::I32(to_i32(&lhs) ^ to_i32(&rhs)),
+        BinOp::ShiftLeft => Value::I32(to_i32(&lhs).wrapping_shl(to_i32(&rhs) as u32 & 31)),
+        BinOp::ShiftRight => Value::I32(to_i32(&lhs).wrapping_shr(to_i32(&rhs) as u32 & 31)),
+        BinOp::ShiftRightUnsigned => {
+            Value::F64(f64::from((to_i32(&lhs) as u32).wrapping_shr(to_i32(&rhs) as u32 & 31)))
+        }
+    }
+}
+
+/// AVM1's abstract equality (`Equals2`): same-type strings compare by
+/// value, `Undefined`/`Null` only ever equal each other, and everything
+/// else falls back to numeric comparison (ECMA-262's `ToNumber` branch).
+fn values_equal(lhs: &Value, rhs: &Value) -> bool {
+    match (lhs, rhs) {
+        (Value::Str(a), Value::Str(b)) => a == b,
+        (Value::Undefined, Value::Undefined)
+        | (Value::Null, Value::Null)
+        | (Value::Undefined, Value::Null)
+        | (Value::Null, Value::Undefined) => true,
+        (Value::Undefined, _) | (Value::Null, _) | (_, Value::Undefined) | (_, Value::Null) => {
+            false
+        }
+        _ => lhs.to_f64() == rhs.to_f64(),
+    }
+}
+
+fn eval_unary(op: UnOp, x: Value) -> Value {
+    match op {
+        UnOp::Not => Value::Bool(!x.to_boolean()),
+    }
+}
+
+/// AVM1's `ToInt32`, needed by the bitwise/shift ops: truncates through an
+/// `f64` round-trip the same way `Value::to_f64` already does for numbers.
+fn to_i32(x: &Value) -> i32 {
+    let x = x.to_f64();
+    if x.is_finite() {
+        x as i64 as i32
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NullHost;
+    impl Host for NullHost {
+        fn call(&mut self, _callee: &Value, _this: &Value, _args: &[Value]) -> Value {
+            Value::Undefined
+        }
+        fn get_url(&mut self, _url: &str, _target: &str) {}
+    }
+
+    // `if (false) {} else {} a = 1 + 2;`, i.e. what `avm1::Code::compile`
+    // emits for the simplest non-trivial `CfgFlow::If`: a `JumpIf`/`Jump`
+    // pair that skips straight to `Binary`/`SetVar`. `Value::OpRes(3)`
+    // refers to `Binary`'s static index in `ops`, not its execution order,
+    // so `resolve` must be able to find it even though the `Jump` at index 1
+    // skipped index 2.
+    #[test]
+    fn run_skips_over_untaken_branch() {
+        let ops = vec![
+            Op::JumpIf(Value::Bool(false), 2),
+            Op::Jump(3),
+            Op::Jump(3),
+            Op::Binary(BinOp::Add, Value::I32(1), Value::I32(2)),
+            Op::SetVar("a".to_string(), Value::OpRes(3)),
+        ];
+        let code = Code { ops };
+
+        let mut frame = Frame(0);
+        let mut vars = HashMap::new();
+        let mut host = NullHost;
+        let mut ctx = Context { frame: &mut frame, vars: &mut vars, host: &mut host };
+        code.run(&mut ctx);
+
+        assert_eq!(vars.get("a").and_then(Value::as_i32), Some(3));
+    }
+}